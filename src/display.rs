@@ -2,8 +2,47 @@ use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::rect;
+use sdl2::render::WindowCanvas;
+use log;
+use crate::parser;
 
-pub fn display_netpbm(data: &Vec<u16>, width: usize, height: usize, max_value: u16) {
+// background a PAM image's alpha channel is blended against when drawn
+const ALPHA_BACKGROUND: u8 = 0;
+
+fn blend(foreground: u8, alpha: f32) -> u8 {
+    (f32::from(foreground) * alpha + f32::from(ALPHA_BACKGROUND) * (1.0 - alpha)) as u8
+}
+
+fn to_u8(sample: u16, max_value: u16) -> u8 {
+    (f32::from(u8::MAX) * f32::from(sample) / f32::from(max_value)) as u8
+}
+
+fn pixel_color(data: &[u16], pixel: usize, samples_per_pixel: usize, max_value: u16) -> Color {
+    if samples_per_pixel >= 3 {
+        let alpha = if samples_per_pixel == 4 { f32::from(data[pixel + 3]) / f32::from(max_value) } else { 1.0 };
+        Color::RGB(
+            blend(to_u8(data[pixel], max_value), alpha),
+            blend(to_u8(data[pixel + 1], max_value), alpha),
+            blend(to_u8(data[pixel + 2], max_value), alpha),
+        )
+    } else {
+        let alpha = if samples_per_pixel == 2 { f32::from(data[pixel + 1]) / f32::from(max_value) } else { 1.0 };
+        let gray = blend(to_u8(data[pixel], max_value), alpha);
+        Color::RGB(gray, gray, gray)
+    }
+}
+
+fn draw_rows(canvas: &mut WindowCanvas, data: &[u16], width: usize, rows: std::ops::Range<usize>, samples_per_pixel: usize, max_value: u16) {
+    for y in rows {
+        for x in 0..width {
+            let pixel = (y * width + x) * samples_per_pixel;
+            canvas.set_draw_color(pixel_color(data, pixel, samples_per_pixel, max_value));
+            canvas.draw_point(rect::Point::new(x as i32, y as i32)).expect("Could not draw point");
+        }
+    }
+}
+
+pub fn display_netpbm(data: &Vec<u16>, width: usize, height: usize, max_value: u16, samples_per_pixel: usize) {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
@@ -14,17 +53,48 @@ pub fn display_netpbm(data: &Vec<u16>, width: usize, height: usize, max_value: u
 
     let mut canvas = window.into_canvas().build().expect("Could not build canvas to show image!");
 
-    for y in 0..height {
-        for x in 0..width {
-            let gray = f32::from(data[y * width + x]) / f32::from(max_value);
-            let gray = (f32::from(u8::MAX) * gray) as u8;
-            canvas.set_draw_color(Color::RGB(gray, gray, gray));
-            let point = rect::Point::new(x as i32, y as i32);
-            canvas.draw_point(point).expect("Could not draw point");
+    draw_rows(&mut canvas, data, width, 0..height, samples_per_pixel, max_value);
+    canvas.present();
+    let mut event_pump = sdl_context.event_pump().expect("Could not get event_pump!");
+    'showing: loop {
+        let event = event_pump.wait_event();
+        match event {
+            Event::Quit { .. } |
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                break 'showing;
+            }
+            _ => {}
         }
     }
-    canvas.present();
+}
+
+/// Like [`display_netpbm`], but decodes `contents` itself and redraws every
+/// [`PROGRESS_ROWS`](parser) scanlines as they are decoded, so large images appear
+/// top-to-bottom while loading instead of only once the whole file has been read.
+pub fn display_netpbm_progressive(contents: &[u8]) -> Result<(), parser::DecoderError> {
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
     let mut event_pump = sdl_context.event_pump().expect("Could not get event_pump!");
+
+    let mut canvas: Option<WindowCanvas> = None;
+    let mut rows_drawn = 0usize;
+
+    let image = parser::parse_progressive(contents, |partial| {
+        let canvas = canvas.get_or_insert_with(|| {
+            let window = video_subsystem.window("netpbm", partial.width as u32, partial.height as u32)
+                .position_centered()
+                .build()
+                .expect("Could not build window to draw image");
+            window.into_canvas().build().expect("Could not build canvas to show image!")
+        });
+        draw_rows(canvas, partial.data, partial.width, rows_drawn..partial.rows_ready, partial.samples_per_pixel, partial.max_value);
+        rows_drawn = partial.rows_ready;
+        canvas.present();
+        // drain the event queue so the window does not appear frozen during a slow decode
+        for _ in event_pump.poll_iter() {}
+    })?;
+    log::debug!("finished progressive decode of {}x{} image", image.width, image.height);
+
     'showing: loop {
         let event = event_pump.wait_event();
         match event {
@@ -35,4 +105,5 @@ pub fn display_netpbm(data: &Vec<u16>, width: usize, height: usize, max_value: u
             _ => {}
         }
     }
+    Ok(())
 }
\ No newline at end of file
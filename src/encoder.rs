@@ -0,0 +1,125 @@
+//! module for encoding images back into netpbm formats
+use std::io;
+use std::io::Write;
+
+use crate::parser::{magic_numbers, format_descriptor, Image, RasterEncoding, TupleType};
+
+/// which netpbm format (and, for PAM, which tuple type) to serialize an [`Image`] as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    PbmAscii,
+    PbmBinary,
+    PgmAscii,
+    PgmBinary,
+    PpmAscii,
+    PpmBinary,
+    Pam(TupleType),
+}
+
+impl Format {
+    fn magic(self) -> &'static [u8] {
+        match self {
+            Format::PbmAscii => magic_numbers::PBM_ASCII,
+            Format::PbmBinary => magic_numbers::PBM_BINARY,
+            Format::PgmAscii => magic_numbers::PGM_ASCII,
+            Format::PgmBinary => magic_numbers::PGM_BINARY,
+            Format::PpmAscii => magic_numbers::PPM_ASCII,
+            Format::PpmBinary => magic_numbers::PPM_BINARY,
+            Format::Pam(_) => magic_numbers::PAM_BINARY,
+        }
+    }
+}
+
+fn tuple_type_name(tuple_type: TupleType) -> &'static [u8] {
+    match tuple_type {
+        TupleType::BlackAndWhite => b"BLACKANDWHITE",
+        TupleType::Grayscale => b"GRAYSCALE",
+        TupleType::Rgb => b"RGB",
+        TupleType::GrayscaleAlpha => b"GRAYSCALE_ALPHA",
+        TupleType::RgbAlpha => b"RGB_ALPHA",
+    }
+}
+
+/// writes `sample` as one 8-bit byte, or two big-endian bytes if `max_value` needs 16 bits —
+/// the inverse of the split-byte logic `decode_binary_row` uses when reading
+fn encode_sample(sample: u16, max_value: u16, out: &mut impl Write) -> io::Result<()> {
+    if max_value > 255 {
+        out.write_all(&sample.to_be_bytes())
+    } else {
+        out.write_all(&[sample as u8])
+    }
+}
+
+/// packs one row of `width` samples into `ceil(width/8)` MSB-first bytes, a zero sample meaning
+/// black (bit 1) and anything else meaning white (bit 0) — the inverse of the unpacking in
+/// [`crate::parser::read_samples`]
+fn encode_packed_bits_row(row: &[u16], width: usize, out: &mut impl Write) -> io::Result<()> {
+    let mut bytes = vec![0u8; width.div_ceil(8)];
+    for (col, &sample) in row.iter().enumerate() {
+        if sample == 0 {
+            bytes[col / 8] |= 1 << (7 - (col % 8));
+        }
+    }
+    out.write_all(&bytes)
+}
+
+fn write_header(width: usize, height: usize, max_value: u16, has_max_value_line: bool, magic: &[u8], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}", std::str::from_utf8(magic).expect("magic numbers are ASCII"))?;
+    writeln!(out, "{width} {height}")?;
+    if has_max_value_line {
+        writeln!(out, "{max_value}")?;
+    }
+    Ok(())
+}
+
+fn encode_generic(image: &Image<u16>, format: Format, out: &mut impl Write) -> io::Result<()> {
+    let descriptor = format_descriptor(format.magic()).expect("Format always maps to a known magic number");
+    write_header(image.width, image.height, image.max_value, descriptor.has_max_value_line, format.magic(), out)?;
+    match descriptor.encoding {
+        RasterEncoding::AsciiSamples => {
+            for sample in &image.data {
+                writeln!(out, "{sample}")?;
+            }
+        }
+        RasterEncoding::AsciiBits => {
+            for &sample in &image.data {
+                out.write_all(if sample == 0 { b"1 " } else { b"0 " })?;
+            }
+        }
+        RasterEncoding::BinarySamples => {
+            for &sample in &image.data {
+                encode_sample(sample, image.max_value, out)?;
+            }
+        }
+        RasterEncoding::BinaryPackedBits => {
+            for row in image.data.chunks(image.width) {
+                encode_packed_bits_row(row, image.width, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// PAM (P7) headers are `KEY VALUE` lines terminated by a lone `ENDHDR` line, the inverse of
+/// the header [`crate::parser::parse_pam`] reads
+fn encode_pam(image: &Image<u16>, tuple_type: TupleType, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}", std::str::from_utf8(magic_numbers::PAM_BINARY).expect("magic numbers are ASCII"))?;
+    writeln!(out, "WIDTH {}", image.width)?;
+    writeln!(out, "HEIGHT {}", image.height)?;
+    writeln!(out, "DEPTH {}", image.samples_per_pixel)?;
+    writeln!(out, "MAXVAL {}", image.max_value)?;
+    writeln!(out, "TUPLTYPE {}", std::str::from_utf8(tuple_type_name(tuple_type)).expect("tuple type names are ASCII"))?;
+    writeln!(out, "ENDHDR")?;
+    for &sample in &image.data {
+        encode_sample(sample, image.max_value, out)?;
+    }
+    Ok(())
+}
+
+/// serializes `image` as `format`, writing the magic number, header and raster bytes to `out`
+pub fn encode(image: &Image<u16>, format: Format, out: &mut impl Write) -> io::Result<()> {
+    match format {
+        Format::Pam(tuple_type) => encode_pam(image, tuple_type, out),
+        _ => encode_generic(image, format, out),
+    }
+}
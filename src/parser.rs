@@ -1,9 +1,6 @@
 //! module for parsing netpbm images
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::io;
-use std::io::ErrorKind;
-use std::ops::{AddAssign, ShlAssign};
 use log;
 
 // Type         	Magic number	    Extension	Colors
@@ -22,12 +19,84 @@ pub mod magic_numbers {
     pub const PAM_BINARY: &[u8] = b"P7";
 }
 
+/// a structured decoding failure, so callers can match on what went wrong instead of a panic
+/// or an opaque `Box<dyn Error>`
+#[derive(Debug)]
+pub enum DecoderError {
+    MagicInvalid(Vec<u8>),
+    UnparsableInt { field: &'static str, bytes: Vec<u8> },
+    MissingField(&'static str),
+    UnsupportedFormat([u8; 2]),
+    TruncatedRaster { expected: usize, got: usize },
+    InvalidTupleType(Vec<u8>),
+}
+
+impl Display for DecoderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecoderError::MagicInvalid(bytes) => write!(f, "invalid magic number: {bytes:?}"),
+            DecoderError::UnparsableInt { field, bytes } => write!(f, "field '{field}' is not a valid integer: {bytes:?}"),
+            DecoderError::MissingField(field) => write!(f, "missing required field '{field}'"),
+            DecoderError::UnsupportedFormat(magic) => write!(f, "unsupported format: {magic:?}"),
+            DecoderError::TruncatedRaster { expected, got } => write!(f, "truncated raster: expected {expected} samples, got {got}"),
+            DecoderError::InvalidTupleType(bytes) => write!(f, "invalid TUPLTYPE: {bytes:?}"),
+        }
+    }
+}
+
+impl Error for DecoderError {}
+
 #[derive(Clone)]
 pub struct Image<F: Clone> {
     pub data: Vec<F>,
     pub width: usize,
     pub height: usize,
     pub max_value: u16,
+    pub samples_per_pixel: usize,
+}
+
+/// layout of a format's raster, used to pick the right reading routine in [`read_samples`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RasterEncoding {
+    /// whitespace-separated decimal samples (P2, P3)
+    AsciiSamples,
+    /// whitespace-separated '0'/'1' tokens, one per bit, no max-value line (P1)
+    AsciiBits,
+    /// 8- or 16-bit big-endian samples, selected by max-value (P5, P6)
+    BinarySamples,
+    /// bits packed MSB-first into bytes, each row padded to a whole byte, no max-value line (P4)
+    BinaryPackedBits,
+}
+
+/// how a format's header and raster are laid out, keyed by magic number
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FormatDescriptor {
+    pub(crate) samples_per_pixel: usize,
+    pub(crate) encoding: RasterEncoding,
+    pub(crate) has_max_value_line: bool,
+}
+
+/// looks up the [`FormatDescriptor`] for a magic number, or `None` for PAM (P7), whose header is
+/// read by [`parse_pam`] instead, and for magic numbers this crate does not know. Shared with [`crate::encoder`] so both sides of the format table
+/// stay in sync.
+pub(crate) fn format_descriptor(magic: &[u8]) -> Option<FormatDescriptor> {
+    match magic {
+        magic_numbers::PBM_ASCII => Some(FormatDescriptor { samples_per_pixel: 1, encoding: RasterEncoding::AsciiBits, has_max_value_line: false }),
+        magic_numbers::PGM_ASCII => Some(FormatDescriptor { samples_per_pixel: 1, encoding: RasterEncoding::AsciiSamples, has_max_value_line: true }),
+        magic_numbers::PPM_ASCII => Some(FormatDescriptor { samples_per_pixel: 3, encoding: RasterEncoding::AsciiSamples, has_max_value_line: true }),
+        magic_numbers::PBM_BINARY => Some(FormatDescriptor { samples_per_pixel: 1, encoding: RasterEncoding::BinaryPackedBits, has_max_value_line: false }),
+        magic_numbers::PGM_BINARY => Some(FormatDescriptor { samples_per_pixel: 1, encoding: RasterEncoding::BinarySamples, has_max_value_line: true }),
+        magic_numbers::PPM_BINARY => Some(FormatDescriptor { samples_per_pixel: 3, encoding: RasterEncoding::BinarySamples, has_max_value_line: true }),
+        _ => None,
+    }
+}
+
+/// header fields common to every format this crate decodes, however they were spelled on disk
+struct Header {
+    width: usize,
+    height: usize,
+    max_value: u16,
+    samples_per_pixel: usize,
 }
 
 #[derive(Debug, PartialEq)]
@@ -56,17 +125,28 @@ impl BytesParser {
         }
     }
 
-    fn take_line<'a>(&mut self, contents: &'a [u8]) -> Result<&'a [u8], Box<dyn Error>> {
+    /// returns the next whitespace-delimited token, or `None` once `contents` is exhausted
+    fn take_line<'a>(&mut self, contents: &'a [u8]) -> Option<&'a [u8]> {
         let len = contents.len();
         log::debug!("take line from contents with size{len}");
         let searchable = &contents[self.whitespace_index..];
-        self.prev_whitespace_index = self.whitespace_index;
-        if let Some(i) = searchable.iter().position(|byte| WHITESPACE_BYTES.contains(byte)) {
-            self.whitespace_index += i + 1;
+        if searchable.is_empty() {
+            return None;
         }
-        let line = &contents[self.prev_whitespace_index..self.whitespace_index - 1];
+        self.prev_whitespace_index = self.whitespace_index;
+        let line = match searchable.iter().position(|byte| WHITESPACE_BYTES.contains(byte)) {
+            Some(i) => {
+                self.whitespace_index += i + 1;
+                &contents[self.prev_whitespace_index..self.whitespace_index - 1]
+            }
+            // no more whitespace before EOF: the remainder is one last, unterminated token
+            None => {
+                self.whitespace_index = contents.len();
+                &contents[self.prev_whitespace_index..self.whitespace_index]
+            }
+        };
         log::debug!("Found line '{line:?}'");
-        Ok(line)
+        Some(line)
     }
 
     pub fn take_rest<'a>(&mut self, contents: &'a [u8]) -> &'a [u8] {
@@ -76,132 +156,360 @@ impl BytesParser {
     }
 }
 
-fn parse_usize(bytes: &[u8]) -> Result<usize, Box<dyn Error>> {
+fn parse_usize(field: &'static str, bytes: &[u8]) -> Result<usize, DecoderError> {
     log::debug!("parse_usize('{bytes:?}')");
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+        return Err(DecoderError::UnparsableInt { field, bytes: bytes.to_vec() });
+    }
     let mut result = 0usize;
     for byte in bytes {
-        result *= 10;
-        result.add_assign((*byte - b'0') as usize);
+        result = result.checked_mul(10)
+            .and_then(|result| result.checked_add((*byte - b'0') as usize))
+            .ok_or_else(|| DecoderError::UnparsableInt { field, bytes: bytes.to_vec() })?;
     }
     log::debug!("result {result}");
     Ok(result)
 }
 
-fn parse_u16(bytes: &[u8]) -> Result<u16, Box<dyn Error>> {
+fn parse_u16(field: &'static str, bytes: &[u8]) -> Result<u16, DecoderError> {
     log::debug!("parse_u16('{bytes:?}')");
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+        return Err(DecoderError::UnparsableInt { field, bytes: bytes.to_vec() });
+    }
     let mut result = 0u16;
     for byte in bytes {
-        result *= 10;
-        result.add_assign((*byte - b'0') as u16);
+        result = result.checked_mul(10)
+            .and_then(|result| result.checked_add((*byte - b'0') as u16))
+            .ok_or_else(|| DecoderError::UnparsableInt { field, bytes: bytes.to_vec() })?;
     }
     log::debug!("result {result}");
     Ok(result)
 }
 
-pub fn parse(contents: &[u8]) -> Result<Image<u16>, Box<dyn Error>> {
+/// tuple type of a PAM (P7) image, as declared by its `TUPLTYPE` header field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TupleType {
+    BlackAndWhite,
+    Grayscale,
+    Rgb,
+    GrayscaleAlpha,
+    RgbAlpha,
+}
+
+fn parse_tuple_type(bytes: &[u8]) -> Result<TupleType, DecoderError> {
+    match bytes {
+        b"BLACKANDWHITE" => Ok(TupleType::BlackAndWhite),
+        b"GRAYSCALE" => Ok(TupleType::Grayscale),
+        b"RGB" => Ok(TupleType::Rgb),
+        b"GRAYSCALE_ALPHA" => Ok(TupleType::GrayscaleAlpha),
+        b"RGB_ALPHA" => Ok(TupleType::RgbAlpha),
+        _ => Err(DecoderError::InvalidTupleType(bytes.to_vec())),
+    }
+}
+
+/// number of scanlines decoded between progress callbacks in [`parse_progressive`]
+const PROGRESS_ROWS: usize = 16;
+
+/// a snapshot of a raster that is still being filled in by a progressive decode
+pub struct PartialImage<'a> {
+    pub data: &'a [u16],
+    pub width: usize,
+    pub height: usize,
+    pub max_value: u16,
+    pub samples_per_pixel: usize,
+    pub rows_ready: usize,
+}
+
+fn decode_binary_row(row_bytes: &[u8], max_value: u16, out: &mut Vec<u16>) {
+    if max_value > 255 {
+        for sample in row_bytes.chunks_exact(2) {
+            out.push(u16::from_be_bytes([sample[0], sample[1]]));
+        }
+    } else {
+        out.extend(row_bytes.iter().map(|b| *b as u16));
+    }
+}
+
+/// Reads `header.height` rows of raster out of `raster` according to `descriptor.encoding`,
+/// appending samples to `data` and invoking `on_rows` every [`PROGRESS_ROWS`] scanlines. This is
+/// the one place that knows how to turn bytes into samples; adding a future subtype is just a
+/// new [`FormatDescriptor`] table entry rather than a new copy of this function.
+fn read_samples(
+    raster: &[u8],
+    header: &Header,
+    descriptor: &FormatDescriptor,
+    data: &mut Vec<u16>,
+    on_rows: &mut dyn for<'a> FnMut(PartialImage<'a>),
+) {
+    let report_row = |row: usize, data: &Vec<u16>, on_rows: &mut dyn for<'a> FnMut(PartialImage<'a>)| {
+        if (row + 1).is_multiple_of(PROGRESS_ROWS) {
+            on_rows(PartialImage {
+                data,
+                width: header.width,
+                height: header.height,
+                max_value: header.max_value,
+                samples_per_pixel: header.samples_per_pixel,
+                rows_ready: row + 1,
+            });
+        }
+    };
+    match descriptor.encoding {
+        RasterEncoding::BinarySamples => {
+            let row_bytes = header.width * header.samples_per_pixel * if header.max_value > 255 { 2 } else { 1 };
+            // chunks_exact(), capped at height, ignores both a short/missing final row (left for
+            // the caller's length check to report as TruncatedRaster) and any trailing bytes
+            // past the raster this format doesn't own (e.g. a conventional trailing newline)
+            for (row, row_bytes) in raster.chunks_exact(row_bytes).take(header.height).enumerate() {
+                decode_binary_row(row_bytes, header.max_value, data);
+                report_row(row, data, on_rows);
+            }
+        }
+        RasterEncoding::BinaryPackedBits => {
+            let row_bytes = header.width.div_ceil(8);
+            // see BinarySamples above: chunks_exact().take(height) keeps a short final row or a
+            // trailing byte past the raster from indexing off the end of a partial last chunk
+            for (row, row_bytes) in raster.chunks_exact(row_bytes).take(header.height).enumerate() {
+                for col in 0..header.width {
+                    let bit = (row_bytes[col / 8] >> (7 - (col % 8))) & 1;
+                    // a set bit is black, the inverse of the grayscale convention
+                    data.push(if bit == 1 { 0 } else { header.max_value });
+                }
+                report_row(row, data, on_rows);
+            }
+        }
+        RasterEncoding::AsciiSamples => {
+            // `raster` is a slice in its own right, disjoint from whatever buffer it was
+            // sliced out of, so it needs a parser whose offsets start back at zero
+            let mut bytes_parser = BytesParser::new();
+            let row_samples = header.width * header.samples_per_pixel;
+            while let Some(some) = bytes_parser.take_line(raster) {
+                if some.is_empty() {
+                    // double white characters will give empty lines, skip them
+                    continue;
+                }
+                match parse_u16("sample", some) {
+                    Ok(val) => {
+                        data.push(val);
+                        if row_samples > 0 && data.len() % row_samples == 0 && data.len() / row_samples <= header.height {
+                            report_row(data.len() / row_samples - 1, data, on_rows);
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Finished parsing ASCII data on Error:{e}");
+                        break;
+                    }  // assume that an error means the end of the ascii data
+                }
+            }
+        }
+        RasterEncoding::AsciiBits => {
+            for byte in raster.iter().filter(|b| !WHITESPACE_BYTES.contains(b)) {
+                match byte {
+                    b'0' => data.push(header.max_value),
+                    b'1' => data.push(0),
+                    _ => {
+                        log::warn!("Ignoring unexpected byte '{byte}' in PBM ASCII data");
+                        continue;
+                    }
+                }
+                if header.width > 0 && data.len() % header.width == 0 && data.len() / header.width <= header.height {
+                    report_row(data.len() / header.width - 1, data, on_rows);
+                }
+            }
+        }
+    }
+}
+
+/// PAM (P7) headers are `KEY VALUE` lines terminated by a lone `ENDHDR` line, rather than
+/// the positional Type/Width/Height/MaxValue sequence the other formats use.
+fn parse_pam(contents: &[u8], on_rows: &mut dyn for<'a> FnMut(PartialImage<'a>)) -> Result<Image<u16>, DecoderError> {
+    log::info!("start parsing PAM contents of size {}", contents.len());
+    let mut bytes_parser = BytesParser::new();
+    bytes_parser.take_line(contents).ok_or(DecoderError::MissingField("magic"))?; // consume the "P7" magic number
+
+    let mut width = None;
+    let mut height = None;
+    let mut depth = None;
+    let mut max_value = None;
+    let mut tuple_type = None;
+    loop {
+        let key = bytes_parser.take_line(contents).ok_or(DecoderError::MissingField("ENDHDR"))?;
+        if key.is_empty() || key.starts_with(b"#") {
+            continue;
+        }
+        if key == b"ENDHDR" {
+            break;
+        }
+        let value = bytes_parser.take_line(contents).ok_or(DecoderError::MissingField("value"))?;
+        match key {
+            b"WIDTH" => width = Some(parse_usize("width", value)?),
+            b"HEIGHT" => height = Some(parse_usize("height", value)?),
+            b"DEPTH" => depth = Some(parse_usize("depth", value)?),
+            b"MAXVAL" => max_value = Some(parse_u16("maxval", value)?),
+            b"TUPLTYPE" => tuple_type = Some(parse_tuple_type(value)?),
+            _ => log::warn!("Ignoring unknown PAM header field '{key:?}'"),
+        }
+    }
+    log::debug!("PAM tuple type: {:?}", tuple_type);
+
+    let width = width.ok_or(DecoderError::MissingField("width"))?;
+    let height = height.ok_or(DecoderError::MissingField("height"))?;
+    let depth = depth.ok_or(DecoderError::MissingField("depth"))?;
+    let max_value = max_value.ok_or(DecoderError::MissingField("maxval"))?;
+
+    // the raster starts on the very next byte, there is no extra whitespace to skip
+    let raster = bytes_parser.take_rest(contents);
+    let mut data = Vec::with_capacity(width * height * depth);
+    let header = Header { width, height, max_value, samples_per_pixel: depth };
+    let descriptor = FormatDescriptor { samples_per_pixel: depth, encoding: RasterEncoding::BinarySamples, has_max_value_line: true };
+    // header is known but no rows have been decoded yet
+    on_rows(PartialImage { data: &data, width, height, max_value, samples_per_pixel: depth, rows_ready: 0 });
+    read_samples(raster, &header, &descriptor, &mut data, on_rows);
+
+    let expected = width * height * depth;
+    if data.len() != expected {
+        return Err(DecoderError::TruncatedRaster { expected, got: data.len() });
+    }
+    on_rows(PartialImage { data: &data, width, height, max_value, samples_per_pixel: depth, rows_ready: height });
+
+    Ok(Image {
+        data,
+        width,
+        height,
+        max_value,
+        samples_per_pixel: depth,
+    })
+}
+
+/// Decode `contents`, returning the fully-built [`Image`] like [`parse`], but additionally
+/// invoking `on_rows` every [`PROGRESS_ROWS`] scanlines (plus once with the header but no rows
+/// yet, and once more with every row when the raster is read) so a caller can redraw as the raster streams in rather than only once decoding
+/// finishes entirely.
+pub fn parse_progressive(contents: &[u8], mut on_rows: impl for<'a> FnMut(PartialImage<'a>)) -> Result<Image<u16>, DecoderError> {
+    parse_impl(contents, &mut on_rows)
+}
+
+pub fn parse(contents: &[u8]) -> Result<Image<u16>, DecoderError> {
+    parse_impl(contents, &mut |_| {})
+}
+
+fn parse_impl(contents: &[u8], on_rows: &mut dyn for<'a> FnMut(PartialImage<'a>)) -> Result<Image<u16>, DecoderError> {
+    if contents.get(0..2) == Some(magic_numbers::PAM_BINARY) {
+        return parse_pam(contents, on_rows);
+    }
     log::info!("start parsing contents of size {}", contents.len());
     let mut data = vec![];
     let mut parse_state = PgmParseState::Type;
     let mut width = None;
     let mut height = None;
     let mut max_value = None;
-    let mut pgm_type = None;
+    let mut descriptor = None;
     let mut bytes_parser = BytesParser::new();
     loop {
-        let line = if parse_state != PgmParseState::Data { bytes_parser.take_line(contents)? } else { bytes_parser.take_rest(contents) };
+        // the header is read token-by-token and ends the loop on EOF so the caller gets a
+        // MissingField error instead of slicing past an exhausted buffer; the raster, once we
+        // reach it, is read in one go via take_rest (an empty raster is handled by read_samples
+        // itself, so it must not be treated as "more header tokens to skip" or the loop never
+        // advances past PgmParseState::Data)
+        let line = if parse_state != PgmParseState::Data {
+            match bytes_parser.take_line(contents) {
+                Some(line) => line,
+                None => break,
+            }
+        } else {
+            bytes_parser.take_rest(contents)
+        };
         log::debug!("loop: line='{:?}'", &line[..usize::min(line.len(), 10)]);
-        if line.is_empty() {
-            continue;
-        }
-        if line.starts_with(b"#") {
-            continue;
+        if parse_state != PgmParseState::Data {
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with(b"#") {
+                continue;
+            }
         }
 
         parse_state = match parse_state {
             PgmParseState::Type => {
                 log::debug!("Parsing p*m type");
-                pgm_type = Some(line);
+                if line.len() != 2 {
+                    return Err(DecoderError::MagicInvalid(line.to_vec()));
+                }
+                let magic = [line[0], line[1]];
+                descriptor = Some(format_descriptor(&magic).ok_or(DecoderError::UnsupportedFormat(magic))?);
                 PgmParseState::Width
             }
             PgmParseState::Width => {
                 log::debug!("parsing width");
-                width = Some(parse_usize(line)?);
+                width = Some(parse_usize("width", line)?);
                 PgmParseState::Height
             }
             PgmParseState::Height => {
                 log::debug!("Parsing height");
-                height = Some(parse_usize(line)?);
-                PgmParseState::MaxValue
+                height = Some(parse_usize("height", line)?);
+                if descriptor.unwrap().has_max_value_line {
+                    PgmParseState::MaxValue
+                } else {
+                    // formats with no max-value line have exactly two possible sample values
+                    max_value = Some(1);
+                    PgmParseState::Data
+                }
             }
             PgmParseState::MaxValue => {
                 log::debug!("Parsing max_value");
-                max_value = Some(parse_u16(line)?);
+                max_value = Some(parse_u16("max_value", line)?);
                 PgmParseState::Data
             }
             PgmParseState::Data => {
-                log::debug!("Parsing data with type '{:?}'; data has length:{}", pgm_type, line.len());
-                match pgm_type.unwrap() {
-                    magic_numbers::PGM_BINARY => {
-                        if max_value.unwrap() > 255 {
-                            for (i, byte) in line.iter().enumerate() {
-                                if i % 2 == 0 {
-                                    data.push(*byte as u16)
-                                } else {
-                                    if let Some(last) = data.last_mut() {
-                                        last.shl_assign(8);
-                                        *last += *byte as u16;
-                                    }
-                                }
-                            }
-                        } else {
-                            data.extend(line.iter().map(|b| *b as u16));
-                        }
-                    }
-                    magic_numbers::PGM_ASCII => {
-                        while let Ok(some) = bytes_parser.take_line(line) {
-                            if some.is_empty() {
-                                // double white characters will give empty lines, skip them
-                                continue;
-                            }
-                            match parse_u16(some) {
-                                Ok(val) => data.push(val),
-                                Err(e) => {
-                                    log::warn!("Finished parsing ASCII data on Error:{e}");
-                                    break;
-                                }  // assume that an error means the end of the ascii data
-                            }
-                        }
-                    }
-                    magic_numbers::PBM_ASCII => { todo!() }
-                    magic_numbers::PBM_BINARY => { todo!() }
-                    magic_numbers::PPM_ASCII => { todo!() }
-                    magic_numbers::PPM_BINARY => { todo!() }
-                    magic_numbers::PAM_BINARY => { todo!() }
-                    &_ => return Err(Box::new(io::Error::new(ErrorKind::Unsupported, "Unkown image format!"))),
-                }
+                log::debug!("Parsing data with length:{}", line.len());
+                let header = Header {
+                    width: width.unwrap(),
+                    height: height.unwrap(),
+                    max_value: max_value.unwrap(),
+                    samples_per_pixel: descriptor.unwrap().samples_per_pixel,
+                };
+                // header is known but no rows have been decoded yet
+                on_rows(PartialImage { data: &data, width: header.width, height: header.height, max_value: header.max_value, samples_per_pixel: header.samples_per_pixel, rows_ready: 0 });
+                read_samples(line, &header, &descriptor.unwrap(), &mut data, on_rows);
+                on_rows(PartialImage { data: &data, width: header.width, height: header.height, max_value: header.max_value, samples_per_pixel: header.samples_per_pixel, rows_ready: header.height });
                 break;
             }
         }
     }
-    let max_value = max_value.expect("Did not get max_value from PGM file");
-    let width = width.expect("Did not get width from PGM file");
-    let height = height.expect("Did not get height from PGM file");
+    let samples_per_pixel = descriptor.ok_or(DecoderError::MissingField("type"))?.samples_per_pixel;
+    let width = width.ok_or(DecoderError::MissingField("width"))?;
+    let height = height.ok_or(DecoderError::MissingField("height"))?;
+    let max_value = max_value.ok_or(DecoderError::MissingField("max_value"))?;
+
+    let expected = width * height * samples_per_pixel;
+    if data.len() != expected {
+        return Err(DecoderError::TruncatedRaster { expected, got: data.len() });
+    }
+
     Ok(Image {
         data,
         width,
         height,
         max_value,
+        samples_per_pixel,
     })
 }
 
 impl Display for Image<u16> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Image { data, width, height, max_value } => {
+            Image { data, width, height, max_value, samples_per_pixel } => {
                 log::debug!("Display image with size:({},{})", width, height);
                 for row in 0..*height {
                     for col in 0..*width {
-                        let gray = f32::from(data[row * width + col]) / f32::from(*max_value);
+                        let pixel = (row * width + col) * samples_per_pixel;
+                        let luminance = if *samples_per_pixel >= 3 {
+                            0.299 * f32::from(data[pixel])
+                                + 0.587 * f32::from(data[pixel + 1])
+                                + 0.114 * f32::from(data[pixel + 2])
+                        } else {
+                            f32::from(data[pixel])
+                        };
+                        let gray = luminance / f32::from(*max_value);
                         let char = if gray < 0.2 {
                             ' '
                         } else if gray < 0.4 {
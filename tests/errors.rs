@@ -0,0 +1,71 @@
+//! malformed- and edge-case-input tests: each case here must either return a `DecoderError`
+//! or decode cleanly, never panic or hang
+use netpbm::parser::{self, DecoderError};
+
+fn assert_err(contents: &[u8]) -> DecoderError {
+    // `Image` has no `Debug` impl, so `Result::expect_err` (which needs `T: Debug` to format an
+    // unexpected `Ok`) isn't usable here
+    match parser::parse(contents) {
+        Err(e) => e,
+        Ok(_) => panic!("expected a DecoderError, not a successful parse"),
+    }
+}
+
+#[test]
+fn truncated_right_after_magic_number_is_missing_field() {
+    match assert_err(b"P2\n") {
+        DecoderError::MissingField(_) => {}
+        other => panic!("expected MissingField, got {other:?}"),
+    }
+}
+
+#[test]
+fn header_with_zero_bytes_of_raster_is_truncated_raster() {
+    match assert_err(b"P5\n2 2\n255\n") {
+        DecoderError::TruncatedRaster { expected: 4, got: 0 } => {}
+        other => panic!("expected TruncatedRaster {{ expected: 4, got: 0 }}, got {other:?}"),
+    }
+}
+
+#[test]
+fn pam_without_endhdr_is_missing_field() {
+    match assert_err(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\n") {
+        DecoderError::MissingField(_) => {}
+        other => panic!("expected MissingField, got {other:?}"),
+    }
+}
+
+#[test]
+fn overflowing_integer_field_is_unparsable_int() {
+    match assert_err(b"P5\n99999999999999999999 2\n255\n") {
+        DecoderError::UnparsableInt { field: "width", .. } => {}
+        other => panic!("expected UnparsableInt for 'width', got {other:?}"),
+    }
+}
+
+#[test]
+fn unrecognized_tuple_type_is_invalid_tuple_type() {
+    let contents = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nTUPLTYPE NOT_A_TYPE\nENDHDR\n";
+    match assert_err(contents) {
+        DecoderError::InvalidTupleType(_) => {}
+        other => panic!("expected InvalidTupleType, got {other:?}"),
+    }
+}
+
+#[test]
+fn trailing_byte_after_a_binary_samples_raster_still_decodes() {
+    let mut contents = b"P5\n2 2\n255\n".to_vec();
+    contents.extend_from_slice(&[10, 20, 30, 40]);
+    contents.push(b'\n'); // a conventional trailing newline, one byte past the real raster
+    let image = parser::parse(&contents).expect("a trailing newline should not break decoding");
+    assert_eq!(image.data, vec![10, 20, 30, 40]);
+}
+
+#[test]
+fn trailing_byte_after_a_packed_bits_raster_does_not_panic() {
+    let mut contents = b"P4\n9 1\n".to_vec();
+    contents.push(0b1010_1010);
+    contents.push(0b1000_0000);
+    contents.push(b'\n'); // a conventional trailing newline, one byte past the real raster
+    parser::parse(&contents).expect("a trailing newline should not panic");
+}
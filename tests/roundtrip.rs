@@ -0,0 +1,72 @@
+//! round-trip tests: encode an image with `encoder::encode`, decode it back with
+//! `parser::parse`, and check the two agree
+use netpbm::encoder::{self, Format};
+use netpbm::parser::{self, Image, TupleType};
+
+fn image(samples_per_pixel: usize, max_value: u16, width: usize, height: usize, fill: impl Fn(usize) -> u16) -> Image<u16> {
+    let data = (0..width * height * samples_per_pixel).map(fill).collect();
+    Image { data, width, height, max_value, samples_per_pixel }
+}
+
+fn roundtrip(image: &Image<u16>, format: Format) -> Image<u16> {
+    let mut encoded = Vec::new();
+    encoder::encode(image, format, &mut encoded).expect("encode should not fail");
+    parser::parse(&encoded).expect("decoding a freshly-encoded image should not fail")
+}
+
+fn assert_roundtrips(original: &Image<u16>, format: Format) {
+    let decoded = roundtrip(original, format);
+    assert_eq!(decoded.width, original.width);
+    assert_eq!(decoded.height, original.height);
+    assert_eq!(decoded.max_value, original.max_value);
+    assert_eq!(decoded.samples_per_pixel, original.samples_per_pixel);
+    assert_eq!(decoded.data, original.data);
+}
+
+#[test]
+fn pbm_ascii_roundtrip() {
+    let original = image(1, 1, 4, 3, |i| (i % 2) as u16);
+    assert_roundtrips(&original, Format::PbmAscii);
+}
+
+#[test]
+fn pbm_binary_roundtrip() {
+    let original = image(1, 1, 9, 2, |i| (i % 2) as u16);
+    assert_roundtrips(&original, Format::PbmBinary);
+}
+
+#[test]
+fn pgm_ascii_roundtrip() {
+    let original = image(1, 255, 3, 2, |i| (i * 17 % 256) as u16);
+    assert_roundtrips(&original, Format::PgmAscii);
+}
+
+#[test]
+fn pgm_binary_8bit_roundtrip() {
+    let original = image(1, 255, 4, 4, |i| (i * 23 % 256) as u16);
+    assert_roundtrips(&original, Format::PgmBinary);
+}
+
+#[test]
+fn pgm_binary_16bit_roundtrip() {
+    let original = image(1, 65535, 4, 4, |i| (i * 4099) as u16);
+    assert_roundtrips(&original, Format::PgmBinary);
+}
+
+#[test]
+fn ppm_ascii_roundtrip() {
+    let original = image(3, 255, 2, 2, |i| (i * 31 % 256) as u16);
+    assert_roundtrips(&original, Format::PpmAscii);
+}
+
+#[test]
+fn ppm_binary_16bit_roundtrip() {
+    let original = image(3, 65535, 2, 2, |i| (i * 4099) as u16);
+    assert_roundtrips(&original, Format::PpmBinary);
+}
+
+#[test]
+fn pam_rgb_alpha_roundtrip() {
+    let original = image(4, 255, 2, 2, |i| (i * 13 % 256) as u16);
+    assert_roundtrips(&original, Format::Pam(TupleType::RgbAlpha));
+}